@@ -1,4 +1,6 @@
 use core::SegmentId;
+use fastfield_codecs::FastFieldCodecType;
+use schema::Field;
 
 
 #[derive(Clone, Debug, RustcDecodable,RustcEncodable)]
@@ -7,6 +9,21 @@ struct DeleteMeta {
     opstamp: u64,
 }
 
+/// Records how a single fast field was encoded in a segment.
+///
+/// Populated at serialize time so that merge policies and users can reason
+/// about expensive codec/field combinations without reopening the segment.
+#[derive(Clone, Debug, RustcDecodable,RustcEncodable)]
+pub struct FastFieldCodecInfo {
+    /// Codec used to encode the field.
+    pub codec_type: FastFieldCodecType,
+    /// Number of bits used per encoded value, or `None` for codecs such as
+    /// `BlockwiseLinear` that use a per-block bit width.
+    pub num_bits: Option<u8>,
+    /// Number of bytes the encoded field occupies on disk.
+    pub compressed_bytes: u64,
+}
+
 /// SegmentMeta contains simple meta information about a segment.
 ///
 /// For instance the number of docs it contains,
@@ -15,7 +32,8 @@ struct DeleteMeta {
 pub struct SegmentMeta {
     segment_id: SegmentId,
     max_doc: u32,
-    deletes: Option<DeleteMeta>, 
+    deletes: Option<DeleteMeta>,
+    fast_field_codecs: Option<Vec<(Field, FastFieldCodecInfo)>>,
 }
 
 impl SegmentMeta {
@@ -27,6 +45,7 @@ impl SegmentMeta {
             segment_id: segment_id,
             max_doc: 0,
             deletes: None,
+            fast_field_codecs: None,
         }
     }
 
@@ -71,6 +90,32 @@ impl SegmentMeta {
         self.deletes.is_some()
     }
 
+    /// Returns the codec information recorded for the given fast field,
+    /// or `None` if the field is not a fast field or predates this metadata.
+    pub fn fast_field_codec(&self, field: Field) -> Option<&FastFieldCodecInfo> {
+        self.fast_field_codecs
+            .as_ref()
+            .and_then(|codecs| {
+                codecs
+                    .iter()
+                    .find(|(codec_field, _)| *codec_field == field)
+                    .map(|(_, info)| info)
+            })
+    }
+
+    /// Returns the total number of bytes used by the segment's fast fields.
+    pub fn fast_field_space_usage(&self) -> u64 {
+        self.fast_field_codecs
+            .as_ref()
+            .map(|codecs| {
+                codecs
+                    .iter()
+                    .map(|(_, info)| info.compressed_bytes)
+                    .sum()
+            })
+            .unwrap_or(0u64)
+    }
+
     #[doc(hidden)]
     pub fn set_max_doc(&mut self, max_doc: u32) {
         self.max_doc = max_doc;
@@ -83,4 +128,17 @@ impl SegmentMeta {
             opstamp: opstamp,
         });
     }
-}
\ No newline at end of file
+
+    #[doc(hidden)]
+    pub fn set_fast_field_codec(&mut self, field: Field, info: FastFieldCodecInfo) {
+        let codecs = self.fast_field_codecs.get_or_insert_with(Vec::new);
+        if let Some(slot) = codecs
+            .iter_mut()
+            .find(|(codec_field, _)| *codec_field == field)
+        {
+            slot.1 = info;
+        } else {
+            codecs.push((field, info));
+        }
+    }
+}
@@ -5,11 +5,12 @@ use std::path::Path;
 use common::BinarySerializable;
 use fastfield_codecs::bitpacked::{BitpackedCodec, BitpackedReader};
 use fastfield_codecs::blockwise_linear::{BlockwiseLinearCodec, BlockwiseLinearReader};
+use fastfield_codecs::compact_space::{CompactSpaceCodec, CompactSpaceReader};
 use fastfield_codecs::linear::{LinearCodec, LinearReader};
 use fastfield_codecs::{Column, FastFieldCodec, FastFieldCodecType};
 
 use super::gcd::open_gcd_from_bytes;
-use super::FastValue;
+use super::{FastValue, FastValueU128};
 use crate::directory::{CompositeFile, Directory, FileSlice, OwnedBytes, RamDirectory, WritePtr};
 use crate::error::DataCorruption;
 use crate::fastfield::{CompositeFastFieldSerializer, FastFieldsWriter, GCDReader};
@@ -70,18 +71,280 @@ impl<Item: FastValue> DynamicFastFieldReader<Item> {
                              allowed.",
                         )
                         .into()),
+                        FastFieldCodecType::CompactSpace => {
+                            return Err(DataCorruption::comment_only(
+                                "CompactSpace is a u128 codec and cannot be wrapped by Gcd.",
+                            )
+                            .into())
+                        }
                     }
                 }
+                FastFieldCodecType::CompactSpace => {
+                    return Err(DataCorruption::comment_only(
+                        "CompactSpace is a u128 fast field codec and cannot be opened as a u64 \
+                         fast field.",
+                    )
+                    .into())
+                }
             };
         Ok(reader)
     }
 
+    /// Sets the null index describing which documents carry a value.
+    fn set_null_index(&mut self, null_index: NullIndex) {
+        match self {
+            Self::Bitpacked(reader) => reader.null_index = null_index,
+            Self::Linear(reader) => reader.null_index = null_index,
+            Self::BlockwiseLinear(reader) => reader.null_index = null_index,
+            Self::BitpackedGCD(reader) => reader.null_index = null_index,
+            Self::LinearGCD(reader) => reader.null_index = null_index,
+            Self::BlockwiseLinearGCD(reader) => reader.null_index = null_index,
+        }
+    }
+
+    /// Returns the value associated to `doc`, or `None` when the document
+    /// carries no value for this optional fast field.
+    ///
+    /// For dense (non-optional) fields this is always `Some`.
+    #[inline]
+    pub fn get_val_opt(&self, doc: u64) -> Option<Item> {
+        match self {
+            Self::Bitpacked(reader) => reader.get_val_opt(doc),
+            Self::Linear(reader) => reader.get_val_opt(doc),
+            Self::BlockwiseLinear(reader) => reader.get_val_opt(doc),
+            Self::BitpackedGCD(reader) => reader.get_val_opt(doc),
+            Self::LinearGCD(reader) => reader.get_val_opt(doc),
+            Self::BlockwiseLinearGCD(reader) => reader.get_val_opt(doc),
+        }
+    }
+
+    /// Iterates over the doc ids that carry a value, in increasing order.
+    pub fn iter_present_doc_ids(&self) -> Box<dyn Iterator<Item = u64> + '_> {
+        match self {
+            Self::Bitpacked(reader) => reader.iter_present_doc_ids(),
+            Self::Linear(reader) => reader.iter_present_doc_ids(),
+            Self::BlockwiseLinear(reader) => reader.iter_present_doc_ids(),
+            Self::BitpackedGCD(reader) => reader.iter_present_doc_ids(),
+            Self::LinearGCD(reader) => reader.iter_present_doc_ids(),
+            Self::BlockwiseLinearGCD(reader) => reader.iter_present_doc_ids(),
+        }
+    }
+
     /// Returns correct the reader wrapped in the `DynamicFastFieldReader` enum for the data.
     pub fn open(file: FileSlice) -> crate::Result<DynamicFastFieldReader<Item>> {
         let mut bytes = file.read_bytes()?;
+        let version = read_format_version(&mut bytes)?;
+        let codec_type = FastFieldCodecType::deserialize(&mut bytes)?;
+        // The null-index footer is only present in versioned (v1+) files;
+        // legacy segments without a header are always dense.
+        let null_index = if version >= 1 {
+            NullIndex::split_from_footer(&mut bytes)?
+        } else {
+            NullIndex::Full
+        };
+        let mut reader = Self::open_from_id(bytes, codec_type)?;
+        reader.set_null_index(null_index);
+        Ok(reader)
+    }
+
+    /// Fills `output` with the `output.len()` contiguous values starting at
+    /// codec index `start`.
+    ///
+    /// Full-segment scans (stats collectors, facet aggregation) should prefer
+    /// this over calling [`Column::get_val`] per document: the enum dispatch is
+    /// resolved once for the whole run rather than per value.
+    ///
+    /// This amortizes only the dispatch, not the per-value unpack. True bulk
+    /// unpacking of a bit-packed run, and exposing `get_range`/`iter` to generic
+    /// `&dyn Column` consumers, requires a defaulted `Column::get_range` in the
+    /// `fastfield_codecs` crate with Bitpacked/Linear overrides; that lives
+    /// outside this crate and is tracked there, so it is not wired up here.
+    #[inline]
+    pub fn get_range(&self, start: u64, output: &mut [Item]) {
+        match self {
+            Self::Bitpacked(reader) => reader.get_range(start, output),
+            Self::Linear(reader) => reader.get_range(start, output),
+            Self::BlockwiseLinear(reader) => reader.get_range(start, output),
+            Self::BitpackedGCD(reader) => reader.get_range(start, output),
+            Self::LinearGCD(reader) => reader.get_range(start, output),
+            Self::BlockwiseLinearGCD(reader) => reader.get_range(start, output),
+        }
+    }
+
+    /// Iterates over every value in codec order.
+    pub fn iter(&self) -> impl Iterator<Item = Item> + '_ {
+        (0..self.num_vals()).map(move |idx| self.get_val(idx))
+    }
+}
+
+/// Magic bytes prefixed to every fast field file.
+const FAST_FIELD_MAGIC: [u8; 4] = *b"TFFF";
+
+/// Current on-disk fast field format version.
+///
+/// Bump this whenever the byte layout changes (a new codec, the null-index
+/// footer, changed GCD framing, ...) so that older readers reject rather than
+/// misread newer segments.
+const FAST_FIELD_FORMAT_VERSION: u16 = 1;
+
+/// Writes the versioned header ([`FAST_FIELD_MAGIC`] + format version) at the
+/// front of a fast field file. Mirrors [`read_format_version`] and must run
+/// before the codec id is serialized.
+pub(crate) fn write_format_version<W: std::io::Write>(wrt: &mut W) -> std::io::Result<()> {
+    wrt.write_all(&FAST_FIELD_MAGIC)?;
+    FAST_FIELD_FORMAT_VERSION.serialize(wrt)
+}
+
+/// Parses the versioned header at the front of `bytes`, leaving `bytes`
+/// pointing at the codec id and returning the format version.
+///
+/// Segments written before the header existed do not carry the magic bytes;
+/// those are reported as version `0` and `bytes` is left untouched so the codec
+/// id can still be read. When the magic is present, a version newer than this
+/// build understands is rejected with a [`DataCorruption`] error rather than
+/// misread.
+fn read_format_version(bytes: &mut OwnedBytes) -> crate::Result<u16> {
+    let data = bytes.as_slice();
+    if data.len() < FAST_FIELD_MAGIC.len() || data[..FAST_FIELD_MAGIC.len()] != FAST_FIELD_MAGIC {
+        return Ok(0);
+    }
+    bytes.advance(FAST_FIELD_MAGIC.len());
+    let version = u16::deserialize(bytes)?;
+    if version > FAST_FIELD_FORMAT_VERSION {
+        return Err(DataCorruption::comment_only(format!(
+            "Fast field format version {version} is newer than the supported version \
+             {FAST_FIELD_FORMAT_VERSION}."
+        ))
+        .into());
+    }
+    Ok(version)
+}
+
+#[derive(Clone)]
+/// Reader for 128-bit fast fields whose values are large and sparsely
+/// distributed (IP addresses, 128-bit ids, ...).
+///
+/// Whole-range bitpacking would waste bits on such fields, so the values are
+/// remapped into a dense "compact" space before being bitpacked. See
+/// [`CompactSpaceCodec`] for the encoding.
+pub enum DynamicFastFieldReaderU128<Item: FastValueU128> {
+    /// Compact-space remapped + bitpacked fastfield data.
+    CompactSpace(U128ReaderCodecWrapper<Item, CompactSpaceReader>),
+}
+
+impl<Item: FastValueU128> DynamicFastFieldReaderU128<Item> {
+    /// Returns the reader wrapped in the `DynamicFastFieldReaderU128` enum for the data.
+    pub fn open_from_id(
+        bytes: OwnedBytes,
+        codec_type: FastFieldCodecType,
+    ) -> crate::Result<DynamicFastFieldReaderU128<Item>> {
+        let reader = match codec_type {
+            FastFieldCodecType::CompactSpace => DynamicFastFieldReaderU128::CompactSpace(
+                CompactSpaceCodec::open_from_bytes(bytes)?.into(),
+            ),
+            _ => {
+                return Err(DataCorruption::comment_only(format!(
+                    "Codec {codec_type:?} is not a valid u128 fast field codec."
+                ))
+                .into())
+            }
+        };
+        Ok(reader)
+    }
+
+    /// Returns the reader wrapped in the `DynamicFastFieldReaderU128` enum for the data.
+    pub fn open(file: FileSlice) -> crate::Result<DynamicFastFieldReaderU128<Item>> {
+        let mut bytes = file.read_bytes()?;
+        let version = read_format_version(&mut bytes)?;
+        // The u128 codecs were introduced together with the versioned header;
+        // a header-less (version 0) file can never be a compact-space field.
+        if version < 1 {
+            return Err(DataCorruption::comment_only(
+                "u128 fast fields require the versioned format header (version >= 1).",
+            )
+            .into());
+        }
         let codec_type = FastFieldCodecType::deserialize(&mut bytes)?;
         Self::open_from_id(bytes, codec_type)
     }
+
+    /// Returns the document ids whose value falls in the inclusive range `range`.
+    ///
+    /// The compact-space mapping is monotonic, so the query bounds are mapped
+    /// into compact space once and the compact codes are scanned directly.
+    pub fn doc_ids_over_range(&self, range: std::ops::RangeInclusive<Item>) -> Vec<u64> {
+        match self {
+            Self::CompactSpace(wrapper) => wrapper
+                .reader
+                .doc_ids_over_range(range.start().to_u128()..=range.end().to_u128()),
+        }
+    }
+}
+
+/// Wrapper for accessing a 128-bit fastfield.
+///
+/// Mirrors [`FastFieldReaderCodecWrapper`], but converts through the 128-bit
+/// value space rather than `u64`.
+#[derive(Clone)]
+pub struct U128ReaderCodecWrapper<Item: FastValueU128, CodecReader> {
+    reader: CodecReader,
+    _phantom: PhantomData<Item>,
+}
+
+impl<Item: FastValueU128, CodecReader> From<CodecReader>
+    for U128ReaderCodecWrapper<Item, CodecReader>
+{
+    fn from(reader: CodecReader) -> Self {
+        U128ReaderCodecWrapper {
+            reader,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Item: FastValueU128, C: Column<u128> + Clone> Column<Item>
+    for U128ReaderCodecWrapper<Item, C>
+{
+    #[inline]
+    fn get_val(&self, idx: u64) -> Item {
+        Item::from_u128(self.reader.get_val(idx))
+    }
+
+    fn min_value(&self) -> Item {
+        Item::from_u128(self.reader.min_value())
+    }
+
+    fn max_value(&self) -> Item {
+        Item::from_u128(self.reader.max_value())
+    }
+
+    fn num_vals(&self) -> u64 {
+        self.reader.num_vals()
+    }
+}
+
+impl<Item: FastValueU128> Column<Item> for DynamicFastFieldReaderU128<Item> {
+    #[inline]
+    fn get_val(&self, idx: u64) -> Item {
+        match self {
+            Self::CompactSpace(reader) => reader.get_val(idx),
+        }
+    }
+    fn min_value(&self) -> Item {
+        match self {
+            Self::CompactSpace(reader) => reader.min_value(),
+        }
+    }
+    fn max_value(&self) -> Item {
+        match self {
+            Self::CompactSpace(reader) => reader.max_value(),
+        }
+    }
+    fn num_vals(&self) -> u64 {
+        match self {
+            Self::CompactSpace(reader) => reader.num_vals(),
+        }
+    }
 }
 
 impl<Item: FastValue> Column<Item> for DynamicFastFieldReader<Item> {
@@ -135,6 +398,7 @@ impl<Item: FastValue> Column<Item> for DynamicFastFieldReader<Item> {
 #[derive(Clone)]
 pub struct FastFieldReaderCodecWrapper<Item: FastValue, CodecReader> {
     reader: CodecReader,
+    null_index: NullIndex,
     _phantom: PhantomData<Item>,
 }
 
@@ -144,6 +408,7 @@ impl<Item: FastValue, CodecReader> From<CodecReader>
     fn from(reader: CodecReader) -> Self {
         FastFieldReaderCodecWrapper {
             reader,
+            null_index: NullIndex::Full,
             _phantom: PhantomData,
         }
     }
@@ -155,6 +420,29 @@ impl<Item: FastValue, D: Column> FastFieldReaderCodecWrapper<Item, D> {
         let data = self.reader.get_val(idx);
         Item::from_u64(data)
     }
+
+    /// Returns the value associated to `doc`, or `None` when the document
+    /// carries no value. See [`DynamicFastFieldReader::get_val_opt`].
+    #[inline]
+    pub(crate) fn get_val_opt(&self, doc: u64) -> Option<Item> {
+        self.null_index
+            .codec_idx(doc)
+            .map(|idx| self.get_u64(idx))
+    }
+
+    fn iter_present_doc_ids(&self) -> Box<dyn Iterator<Item = u64> + '_> {
+        self.null_index.iter_present_doc_ids(self.reader.num_vals())
+    }
+
+    /// Backing implementation of [`DynamicFastFieldReader::get_range`] for a
+    /// single inner reader: reads the contiguous run through the codec without
+    /// re-resolving the enum dispatch per value.
+    #[inline]
+    pub(crate) fn get_range(&self, start: u64, output: &mut [Item]) {
+        for (offset, out) in output.iter_mut().enumerate() {
+            *out = self.get_u64(start + offset as u64);
+        }
+    }
 }
 
 impl<Item: FastValue, C: Column + Clone> Column<Item> for FastFieldReaderCodecWrapper<Item, C> {
@@ -229,3 +517,203 @@ impl<Item: FastValue> From<Vec<Item>> for DynamicFastFieldReader<Item> {
         DynamicFastFieldReader::open(field_file).unwrap()
     }
 }
+
+/// Number of 64-bit words per rank block in a [`SparseBitset`].
+///
+/// A cumulative popcount is stored once per block so that translating a
+/// `doc_id` to its dense codec index stays O(1).
+const NULL_INDEX_BLOCK_WORDS: usize = 8;
+
+/// Footer describing which documents carry a value for a fast field.
+///
+/// It is appended after the codec payload and parsed by
+/// [`DynamicFastFieldReader::open`]. The common dense case is
+/// [`NullIndex::Full`] and carries no overhead.
+#[derive(Clone)]
+pub enum NullIndex {
+    /// Every document has exactly one value; the dense codec index equals the
+    /// doc id.
+    Full,
+    /// Only a subset of the documents carry a value.
+    SparseBitset(SparseBitset),
+}
+
+impl NullIndex {
+    /// Splits the null-index footer off the end of `bytes`, leaving `bytes`
+    /// pointing at the codec payload.
+    fn split_from_footer(bytes: &mut OwnedBytes) -> crate::Result<NullIndex> {
+        let data = bytes.as_slice();
+        let tag = *data.last().ok_or_else(|| {
+            DataCorruption::comment_only("Fast field file is missing its null-index footer.")
+        })?;
+        match tag {
+            0 => {
+                let end = data.len() - 1;
+                *bytes = bytes.slice(0..end);
+                Ok(NullIndex::Full)
+            }
+            1 => {
+                let len_pos = data.len() - 1 - 4;
+                let footer_len = u32::from_le_bytes(
+                    data[len_pos..len_pos + 4]
+                        .try_into()
+                        .expect("slice is exactly 4 bytes"),
+                ) as usize;
+                let payload_end = len_pos - footer_len;
+                let footer_bytes = bytes.slice(payload_end..len_pos);
+                let sparse = SparseBitset::open(footer_bytes)?;
+                *bytes = bytes.slice(0..payload_end);
+                Ok(NullIndex::SparseBitset(sparse))
+            }
+            other => Err(DataCorruption::comment_only(format!(
+                "Unknown null-index footer tag {other}."
+            ))
+            .into()),
+        }
+    }
+
+    /// Appends the null-index footer after the codec payload.
+    ///
+    /// The dense [`NullIndex::Full`] case writes a single `0` tag byte; the
+    /// sparse case writes the bitset payload, its `u32` length, and a `1` tag
+    /// byte so that [`NullIndex::split_from_footer`] can walk back from the end
+    /// of the file. Callers must only emit this once the versioned header is in
+    /// place (see [`write_format_version`]).
+    pub(crate) fn serialize<W: std::io::Write>(&self, wrt: &mut W) -> std::io::Result<()> {
+        match self {
+            NullIndex::Full => wrt.write_all(&[0u8]),
+            NullIndex::SparseBitset(bitset) => {
+                let mut payload = Vec::new();
+                bitset.serialize(&mut payload)?;
+                wrt.write_all(&payload)?;
+                wrt.write_all(&(payload.len() as u32).to_le_bytes())?;
+                wrt.write_all(&[1u8])
+            }
+        }
+    }
+
+    /// Builds the null index for a field with `max_doc` documents, given the
+    /// sorted doc ids that carry a value.
+    ///
+    /// Returns [`NullIndex::Full`] when every document is present (the dense,
+    /// zero-overhead case) and otherwise a [`SparseBitset`] with its block ranks
+    /// precomputed.
+    pub(crate) fn build(max_doc: u64, present: &[u64]) -> NullIndex {
+        if present.len() as u64 == max_doc {
+            NullIndex::Full
+        } else {
+            NullIndex::SparseBitset(SparseBitset::from_present_doc_ids(
+                max_doc,
+                present.iter().copied(),
+            ))
+        }
+    }
+
+    /// Translates a `doc_id` into the dense codec index, or `None` when the
+    /// document carries no value.
+    #[inline]
+    fn codec_idx(&self, doc: u64) -> Option<u64> {
+        match self {
+            NullIndex::Full => Some(doc),
+            NullIndex::SparseBitset(bitset) => bitset.codec_idx(doc),
+        }
+    }
+
+    fn iter_present_doc_ids(&self, num_vals: u64) -> Box<dyn Iterator<Item = u64> + '_> {
+        match self {
+            NullIndex::Full => Box::new(0..num_vals),
+            NullIndex::SparseBitset(bitset) => Box::new(bitset.iter_present_doc_ids()),
+        }
+    }
+}
+
+/// A bitvector marking, for every `doc_id` in `0..max_doc`, whether the
+/// document carries a value, together with precomputed block ranks so that the
+/// dense codec index is reachable in O(1).
+#[derive(Clone)]
+pub struct SparseBitset {
+    max_doc: u64,
+    words: Vec<u64>,
+    /// Cumulative popcount of all words strictly below each block of
+    /// [`NULL_INDEX_BLOCK_WORDS`] words.
+    block_ranks: Vec<u64>,
+}
+
+impl SparseBitset {
+    /// Builds a sparse null index for `max_doc` documents from the doc ids that
+    /// carry a value.
+    ///
+    /// Sets the corresponding bits and precomputes [`block_ranks`]: the
+    /// cumulative popcount of every word, snapshotted once per block of
+    /// [`NULL_INDEX_BLOCK_WORDS`] words so that [`SparseBitset::codec_idx`] stays
+    /// O(1).
+    fn from_present_doc_ids(max_doc: u64, present: impl IntoIterator<Item = u64>) -> SparseBitset {
+        let num_words = (max_doc as usize + 63) / 64;
+        let mut words = vec![0u64; num_words];
+        for doc in present {
+            words[(doc / 64) as usize] |= 1u64 << (doc % 64);
+        }
+        let mut block_ranks = Vec::with_capacity(num_words / NULL_INDEX_BLOCK_WORDS + 1);
+        let mut rank = 0u64;
+        for (word_idx, word) in words.iter().enumerate() {
+            if word_idx % NULL_INDEX_BLOCK_WORDS == 0 {
+                block_ranks.push(rank);
+            }
+            rank += word.count_ones() as u64;
+        }
+        SparseBitset {
+            max_doc,
+            words,
+            block_ranks,
+        }
+    }
+
+    fn open(mut bytes: OwnedBytes) -> crate::Result<SparseBitset> {
+        let max_doc = u64::deserialize(&mut bytes)?;
+        let words = Vec::<u64>::deserialize(&mut bytes)?;
+        let block_ranks = Vec::<u64>::deserialize(&mut bytes)?;
+        Ok(SparseBitset {
+            max_doc,
+            words,
+            block_ranks,
+        })
+    }
+
+    fn serialize<W: std::io::Write>(&self, wrt: &mut W) -> std::io::Result<()> {
+        self.max_doc.serialize(wrt)?;
+        self.words.serialize(wrt)?;
+        self.block_ranks.serialize(wrt)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn codec_idx(&self, doc: u64) -> Option<u64> {
+        if doc >= self.max_doc {
+            return None;
+        }
+        let word_idx = (doc / 64) as usize;
+        let bit = doc % 64;
+        let word = self.words[word_idx];
+        if word & (1u64 << bit) == 0 {
+            return None;
+        }
+        let block = word_idx / NULL_INDEX_BLOCK_WORDS;
+        let mut rank = self.block_ranks[block];
+        for word in &self.words[block * NULL_INDEX_BLOCK_WORDS..word_idx] {
+            rank += word.count_ones() as u64;
+        }
+        rank += (word & ((1u64 << bit) - 1)).count_ones() as u64;
+        Some(rank)
+    }
+
+    fn iter_present_doc_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        let max_doc = self.max_doc;
+        self.words.iter().enumerate().flat_map(move |(word_idx, &word)| {
+            let base = word_idx as u64 * 64;
+            (0..64)
+                .filter(move |bit| word & (1u64 << bit) != 0)
+                .map(move |bit| base + bit)
+                .take_while(move |&doc| doc < max_doc)
+        })
+    }
+}